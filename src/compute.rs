@@ -0,0 +1,213 @@
+//! A work-efficient (Blelloch) parallel prefix-sum over `f32` storage buffers, run on the GPU.
+//!
+//! Each workgroup scans one block of `BLOCK_SIZE` elements in `var<workgroup>` shared memory
+//! (up-sweep then down-sweep), leaving the block's total in a per-block-sums buffer. That
+//! buffer is itself scanned (recursing on the host, one GPU dispatch per level) and the
+//! resulting block offsets are added back into every element.
+
+use wgpu::util::DeviceExt;
+
+/// Threads per workgroup. Each thread handles two elements, so it must stay within
+/// `Limits::max_compute_invocations_per_workgroup` while `BLOCK_SIZE` stays within the
+/// workgroup's shared-memory budget.
+const WORKGROUP_SIZE: u32 = 256;
+const BLOCK_SIZE: u32 = 2 * WORKGROUP_SIZE;
+
+/// The compute pipelines backing [`State::scan`](crate::State::scan).
+pub struct ScanPipelines {
+    scan_layout: wgpu::BindGroupLayout,
+    scan_pipeline: wgpu::ComputePipeline,
+    add_layout: wgpu::BindGroupLayout,
+    add_pipeline: wgpu::ComputePipeline,
+}
+
+impl ScanPipelines {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let scan_shader = device.create_shader_module(wgpu::include_wgsl!("shaders/scan.wgsl"));
+        let add_shader =
+            device.create_shader_module(wgpu::include_wgsl!("shaders/scan_add.wgsl"));
+
+        let storage_entry = |binding: u32, read_only: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let scan_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("scan bind group layout"),
+            entries: &[storage_entry(0, false), storage_entry(1, false)],
+        });
+        let scan_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("scan pipeline layout"),
+                bind_group_layouts: &[&scan_layout],
+                push_constant_ranges: &[],
+            });
+        let scan_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("scan pipeline"),
+            layout: Some(&scan_pipeline_layout),
+            module: &scan_shader,
+            entry_point: "scan_block",
+        });
+
+        let add_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("scan add bind group layout"),
+            entries: &[storage_entry(0, false), storage_entry(1, true)],
+        });
+        let add_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("scan add pipeline layout"),
+            bind_group_layouts: &[&add_layout],
+            push_constant_ranges: &[],
+        });
+        let add_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("scan add pipeline"),
+            layout: Some(&add_pipeline_layout),
+            module: &add_shader,
+            entry_point: "add_offsets",
+        });
+
+        Self {
+            scan_layout,
+            scan_pipeline,
+            add_layout,
+            add_pipeline,
+        }
+    }
+
+    /// Computes the exclusive prefix sum of `input`, returning a `Vec` of the same length.
+    pub fn scan(&self, device: &wgpu::Device, queue: &wgpu::Queue, input: &[f32]) -> Vec<f32> {
+        let len = input.len();
+        if len == 0 {
+            return Vec::new();
+        }
+
+        // pad the final block with the identity element (0) so a partial block still scans
+        // correctly
+        let num_blocks = (len as u32).div_ceil(BLOCK_SIZE).max(1);
+        let padded_len = (num_blocks * BLOCK_SIZE) as usize;
+        let mut padded = input.to_vec();
+        padded.resize(padded_len, 0.0);
+
+        let data_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("scan data buffer"),
+            contents: bytemuck::cast_slice(&padded),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+        });
+        let block_sums_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("scan block sums buffer"),
+            size: (num_blocks as u64) * std::mem::size_of::<f32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let scan_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("scan bind group"),
+            layout: &self.scan_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: data_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: block_sums_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("scan command encoder"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("scan block pass"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.scan_pipeline);
+            pass.set_bind_group(0, &scan_bind_group, &[]);
+            pass.dispatch_workgroups(num_blocks, 1, 1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        if num_blocks == 1 {
+            return read_buffer(device, queue, &data_buffer, len);
+        }
+
+        // the per-block totals are themselves an array to scan; recursing on the host turns
+        // each level of the tree into one more GPU dispatch
+        let block_sums = read_buffer(device, queue, &block_sums_buffer, num_blocks as usize);
+        let scanned_block_sums = self.scan(device, queue, &block_sums);
+
+        let offsets_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("scan offsets buffer"),
+            contents: bytemuck::cast_slice(&scanned_block_sums),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let add_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("scan add bind group"),
+            layout: &self.add_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: data_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: offsets_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("scan add command encoder"),
+        });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("scan add offsets pass"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.add_pipeline);
+            pass.set_bind_group(0, &add_bind_group, &[]);
+            pass.dispatch_workgroups(num_blocks, 1, 1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        read_buffer(device, queue, &data_buffer, len)
+    }
+}
+
+/// Copies `len` `f32`s out of `buffer` through a `MAP_READ` staging buffer.
+fn read_buffer(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer, len: usize) -> Vec<f32> {
+    let size = (len * std::mem::size_of::<f32>()) as u64;
+    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("scan staging buffer"),
+        size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("scan readback command encoder"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging_buffer, 0, size);
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = staging_buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |result| {
+        result.expect("failed to map scan staging buffer");
+    });
+    device.poll(wgpu::Maintain::Wait);
+
+    let result = bytemuck::cast_slice(&slice.get_mapped_range())[..len].to_vec();
+    staging_buffer.unmap();
+    result
+}
@@ -1,8 +1,15 @@
+mod compute;
+mod post;
+
 use std::iter::once;
 
+use bytemuck::{Pod, Zeroable};
+use compute::ScanPipelines;
+#[cfg(not(target_arch = "wasm32"))]
 use futures::executor::block_on;
 use log::{debug, error};
-use wgpu::{include_wgsl, SurfaceConfiguration};
+use post::{Filter, PostProcess, PostTexture};
+use wgpu::{include_wgsl, util::DeviceExt, SurfaceConfiguration};
 use winit::{
     dpi::PhysicalSize,
     event::*,
@@ -11,6 +18,94 @@ use winit::{
     window::{Window, WindowBuilder},
 };
 
+// the vertex format fed to the pipeline
+// `Pod`/`Zeroable` let us reinterpret a slice of these as raw bytes for `create_buffer_init`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct Vertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+impl Vertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3];
+
+    fn layout() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+// example quad, two triangles sharing an edge via the index buffer
+const VERTICES: &[Vertex] = &[
+    Vertex { position: [-0.5, -0.5, 0.0], color: [1.0, 0.0, 0.0] },
+    Vertex { position: [0.5, -0.5, 0.0], color: [0.0, 1.0, 0.0] },
+    Vertex { position: [0.5, 0.5, 0.0], color: [0.0, 0.0, 1.0] },
+    Vertex { position: [-0.5, 0.5, 0.0], color: [1.0, 1.0, 0.0] },
+];
+
+const INDICES: &[u16] = &[0, 1, 2, 0, 2, 3];
+
+// mirrors the `ScreenSize` uniform in shader.wgsl, used by the position-derived pipeline to
+// turn clip-space pixels back into a 0..1 uv
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct ScreenSize {
+    size: [f32; 2],
+}
+
+/// Knobs for [`State::new`] that would otherwise require recompiling to change: which present
+/// mode to start with, which GPU to prefer, whether to allow falling back to a software
+/// adapter, and whether the window composites with alpha.
+#[derive(Copy, Clone, Debug)]
+pub struct StateConfig {
+    pub present_mode: wgpu::PresentMode,
+    pub power_preference: wgpu::PowerPreference,
+    pub force_fallback_adapter: bool,
+    /// Whether the window and surface should composite with alpha instead of opaquely. Needed
+    /// on macOS for the transparent titlebar (see `run`) to actually blend the clear color
+    /// underneath it; the clear color's alpha is set to 0 when this is on.
+    pub transparent: bool,
+}
+
+impl Default for StateConfig {
+    fn default() -> Self {
+        Self {
+            // fifo is equivalent to vsync (guaranteed to be supported)
+            present_mode: wgpu::PresentMode::Fifo,
+            power_preference: wgpu::PowerPreference::default(),
+            force_fallback_adapter: false,
+            transparent: cfg!(target_os = "macos"),
+        }
+    }
+}
+
+impl StateConfig {
+    pub fn with_present_mode(mut self, present_mode: wgpu::PresentMode) -> Self {
+        self.present_mode = present_mode;
+        self
+    }
+
+    pub fn with_power_preference(mut self, power_preference: wgpu::PowerPreference) -> Self {
+        self.power_preference = power_preference;
+        self
+    }
+
+    pub fn with_force_fallback_adapter(mut self, force_fallback_adapter: bool) -> Self {
+        self.force_fallback_adapter = force_fallback_adapter;
+        self
+    }
+
+    pub fn with_transparent(mut self, transparent: bool) -> Self {
+        self.transparent = transparent;
+        self
+    }
+}
+
 struct State<'w> {
     surface: wgpu::Surface<'w>,
     surface_config: wgpu::SurfaceConfiguration,
@@ -19,15 +114,41 @@ struct State<'w> {
     queue: wgpu::Queue,
     size: PhysicalSize<u32>,
     pipeline: wgpu::RenderPipeline,
+    pipeline_position: wgpu::RenderPipeline,
+    use_color: bool,
+    screen_size_buffer: wgpu::Buffer,
+    screen_size_bind_group: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    num_indices: u32,
+    scene_texture: PostTexture,
+    ping_texture: PostTexture,
+    pong_texture: PostTexture,
+    post_process: PostProcess,
+    scan_pipelines: ScanPipelines,
+    available_present_modes: Vec<wgpu::PresentMode>,
+    present_mode_index: usize,
+    clear_color: wgpu::Color,
 }
 
 impl<'w> State<'w> {
-    async fn new(window: &'w Window) -> Self {
+    async fn new(window: &'w Window, config: StateConfig) -> Self {
         let size = window.inner_size();
 
+        // webgl2 only exposes the GL backend, so we can't rely on the env/all-backends default
+        #[cfg(not(target_arch = "wasm32"))]
+        let backends = wgpu::util::backend_bits_from_env().unwrap_or_else(wgpu::Backends::all);
+        #[cfg(target_arch = "wasm32")]
+        let backends = wgpu::Backends::GL;
+        debug!("backends: {:?}", backends);
+
         // an instance is the first object that wgpu needs to create
-        // it is mainly used to create the surface and adapter
-        let instance = wgpu::Instance::default();
+        // it is mainly used to create the surface and adapter; restricting it to `backends`
+        // keeps `WGPU_BACKEND` honored the way `initialize_adapter_from_env_or_default` used to
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends,
+            ..Default::default()
+        });
 
         // a surface is a platform-specific object that is used to present rendered images
         // to the screen
@@ -37,16 +158,38 @@ impl<'w> State<'w> {
         debug!("surface: {:?}", surface);
 
         // an adapter is the actual handle to the gpu
-        let backends = wgpu::util::backend_bits_from_env().unwrap_or_else(wgpu::Backends::all);
-        debug!("backends: {:?}", backends);
-        for adapter in instance.enumerate_adapters(backends) {
-            let info = adapter.get_info();
-            debug!("adapter: {:?}", info);
+        // `enumerate_adapters` walks wgpu-core's backend list directly, which isn't available
+        // through the web backend wasm32 builds use
+        #[cfg(not(target_arch = "wasm32"))]
+        let adapters = instance.enumerate_adapters(backends);
+        #[cfg(not(target_arch = "wasm32"))]
+        for adapter in &adapters {
+            debug!("adapter: {:?}", adapter.get_info());
         }
 
-        let adapter = wgpu::util::initialize_adapter_from_env_or_default(&instance, Some(&surface))
-            .await
-            .expect("failed to create adapter");
+        // `WGPU_ADAPTER_NAME` used to be honored by `initialize_adapter_from_env_or_default`;
+        // keep selecting by name when it's set, otherwise fall back to `request_adapter` so
+        // `power_preference`/`force_fallback_adapter` from `config` take effect
+        #[cfg(not(target_arch = "wasm32"))]
+        let named_adapter = std::env::var("WGPU_ADAPTER_NAME").ok().and_then(|name| {
+            adapters
+                .into_iter()
+                .find(|adapter| adapter.get_info().name.to_lowercase().contains(&name.to_lowercase()))
+        });
+        #[cfg(target_arch = "wasm32")]
+        let named_adapter: Option<wgpu::Adapter> = None;
+
+        let adapter = match named_adapter {
+            Some(adapter) => adapter,
+            None => instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: config.power_preference,
+                    compatible_surface: Some(&surface),
+                    force_fallback_adapter: config.force_fallback_adapter,
+                })
+                .await
+                .expect("failed to create adapter"),
+        };
 
         // create the device and the queue
         let (device, queue) = adapter
@@ -57,7 +200,11 @@ impl<'w> State<'w> {
                     // provided for unsupported hardware
                     required_features: wgpu::Features::empty(),
                     // they describe the limits of each type of resource we can create
+                    // webgl2 doesn't support the full feature set, so it needs its own limits
+                    #[cfg(not(target_arch = "wasm32"))]
                     required_limits: wgpu::Limits::default(),
+                    #[cfg(target_arch = "wasm32")]
+                    required_limits: wgpu::Limits::downlevel_webgl2_defaults(),
                     label: None,
                 },
                 None,
@@ -68,17 +215,41 @@ impl<'w> State<'w> {
         // configure the surface
         let surface_capabilities = surface.get_capabilities(&adapter);
         let surface_format = surface_capabilities.formats[0];
+        // on macOS the titlebar is transparent (see `run`), so the surface needs an alpha mode
+        // that actually blends the clear color underneath it instead of compositing opaquely
+        let alpha_mode = if config.transparent
+            && surface_capabilities
+                .alpha_modes
+                .contains(&wgpu::CompositeAlphaMode::PostMultiplied)
+        {
+            wgpu::CompositeAlphaMode::PostMultiplied
+        } else {
+            wgpu::CompositeAlphaMode::Auto
+        };
+
+        // fall back to fifo (guaranteed to be supported) if the requested mode isn't
+        let available_present_modes = surface_capabilities.present_modes.clone();
+        let present_mode = if available_present_modes.contains(&config.present_mode) {
+            config.present_mode
+        } else {
+            wgpu::PresentMode::Fifo
+        };
+        let present_mode_index = available_present_modes
+            .iter()
+            .position(|mode| *mode == present_mode)
+            .unwrap_or(0);
+
         let surface_config = SurfaceConfiguration {
             // how will the texture be used
-            // render_attachment specifies that it will be written to the screen
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            // render_attachment specifies that it will be written to the screen, copy_dst lets
+            // the scene texture be blitted straight across when the filter stack is empty
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_DST,
             // how will it be stored on the gpu
             format: surface_format,
             width: size.width,
             height: size.height,
-            // fifo is equivalent to vsync (guaranteed to be supported)
-            present_mode: wgpu::PresentMode::Fifo,
-            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            present_mode,
+            alpha_mode,
             view_formats: vec![surface_format],
             desired_maximum_frame_latency: 2,
         };
@@ -90,6 +261,38 @@ impl<'w> State<'w> {
         // load the sample shader
         let shader = device.create_shader_module(include_wgsl!("shader.wgsl"));
 
+        // the position-derived pipeline needs to know the surface size to turn clip-space
+        // pixels back into a 0..1 uv, so it gets its own bind group with a small uniform
+        let screen_size_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("screen size uniform buffer"),
+            contents: bytemuck::cast_slice(&[ScreenSize {
+                size: [size.width as f32, size.height as f32],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let screen_size_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("screen size bind group layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        let screen_size_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("screen size bind group"),
+            layout: &screen_size_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: screen_size_buffer.as_entire_binding(),
+            }],
+        });
+
         // create the render pipeline
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("main render pipeline layout"),
@@ -101,7 +304,7 @@ impl<'w> State<'w> {
             vertex: wgpu::VertexState {
                 module: &shader,
                 entry_point: "vert",
-                buffers: &[],
+                buffers: &[Vertex::layout()],
             },
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
@@ -118,6 +321,77 @@ impl<'w> State<'w> {
             multiview: None,
         });
 
+        // a second pipeline sharing the same vertex stage, used to demo switching at runtime:
+        // instead of the vertex color it derives the fragment color from screen position
+        let pipeline_position_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("position-derived color render pipeline layout"),
+                bind_group_layouts: &[&screen_size_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline_position = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("position-derived color render pipeline"),
+            layout: Some(&pipeline_position_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vert",
+                buffers: &[Vertex::layout()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "frag_position",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            multisample: wgpu::MultisampleState::default(),
+            depth_stencil: None,
+            multiview: None,
+        });
+
+        // upload the example geometry
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("vertex buffer"),
+            contents: bytemuck::cast_slice(VERTICES),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("index buffer"),
+            contents: bytemuck::cast_slice(INDICES),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let num_indices = INDICES.len() as u32;
+
+        // alpha is 0 when transparent so the window content behind the surface shows through
+        // instead of being tinted by the clear color
+        let clear_color = wgpu::Color {
+            r: 0.3,
+            g: 0.5,
+            b: 0.9,
+            a: if config.transparent { 0.0 } else { 1.0 },
+        };
+
+        // the scene is rendered offscreen first so the post-process stack can run over it
+        // before anything reaches the surface
+        let scene_texture = PostTexture::with_usage(
+            &device,
+            &surface_config,
+            "scene texture",
+            wgpu::TextureUsages::COPY_SRC,
+        );
+        let ping_texture = PostTexture::new(&device, &surface_config, "post-process ping texture");
+        let pong_texture = PostTexture::new(&device, &surface_config, "post-process pong texture");
+
+        let mut post_process = PostProcess::new(&device, surface_config.format);
+        post_process.push(&device, Filter::Grayscale);
+        post_process.push(&device, Filter::Crt);
+        post_process.push(&device, Filter::BloomThreshold);
+
+        let scan_pipelines = ScanPipelines::new(&device);
+
         Self {
             surface,
             surface_config,
@@ -126,18 +400,94 @@ impl<'w> State<'w> {
             queue,
             size,
             pipeline,
+            pipeline_position,
+            use_color: true,
+            screen_size_buffer,
+            screen_size_bind_group,
+            vertex_buffer,
+            index_buffer,
+            num_indices,
+            scene_texture,
+            ping_texture,
+            pong_texture,
+            post_process,
+            scan_pipelines,
+            available_present_modes,
+            present_mode_index,
+            clear_color,
         }
     }
 
+    /// Cycles to the next present mode supported by the surface and reconfigures it.
+    fn cycle_present_mode(&mut self) {
+        self.present_mode_index = (self.present_mode_index + 1) % self.available_present_modes.len();
+        self.surface_config.present_mode = self.available_present_modes[self.present_mode_index];
+        self.surface.configure(&self.device, &self.surface_config);
+        debug!("present mode: {:?}", self.surface_config.present_mode);
+    }
+
+    /// Computes the exclusive prefix sum of `input` on the GPU using a work-efficient
+    /// (Blelloch) parallel scan.
+    #[allow(dead_code)]
+    fn scan(&self, input: &[f32]) -> Vec<f32> {
+        self.scan_pipelines.scan(&self.device, &self.queue, input)
+    }
+
     fn resize(&mut self, new_size: PhysicalSize<u32>) {
         self.size = new_size;
         self.surface_config.width = new_size.width;
         self.surface_config.height = new_size.height;
         self.surface.configure(&self.device, &self.surface_config);
+
+        // the offscreen textures are sized to the surface, so they need to be rebuilt too
+        self.scene_texture = PostTexture::with_usage(
+            &self.device,
+            &self.surface_config,
+            "scene texture",
+            wgpu::TextureUsages::COPY_SRC,
+        );
+        self.ping_texture =
+            PostTexture::new(&self.device, &self.surface_config, "post-process ping texture");
+        self.pong_texture =
+            PostTexture::new(&self.device, &self.surface_config, "post-process pong texture");
+
+        // keep the position-derived pipeline's uv mapping in sync with the new surface size
+        self.queue.write_buffer(
+            &self.screen_size_buffer,
+            0,
+            bytemuck::cast_slice(&[ScreenSize {
+                size: [new_size.width as f32, new_size.height as f32],
+            }]),
+        );
+    }
+
+    /// Appends a filter to the end of the post-process stack.
+    #[allow(dead_code)]
+    fn push_filter(&mut self, filter: Filter) {
+        self.post_process.push(&self.device, filter);
     }
 
-    fn input(&mut self, _event: &KeyEvent) -> bool {
-        false
+    /// Removes the last filter in the post-process stack, if any.
+    #[allow(dead_code)]
+    fn pop_filter(&mut self) {
+        self.post_process.pop();
+    }
+
+    fn input(&mut self, event: &KeyEvent) -> bool {
+        if !event.state.is_pressed() {
+            return false;
+        }
+        match event.physical_key {
+            PhysicalKey::Code(KeyCode::Space) => {
+                self.use_color = !self.use_color;
+                true
+            }
+            PhysicalKey::Code(KeyCode::KeyP) => {
+                self.cycle_present_mode();
+                true
+            }
+            _ => false,
+        }
     }
 
     fn update(&mut self) {
@@ -161,17 +511,14 @@ impl<'w> State<'w> {
 
         // create a render pass using the encoder
         // this has all the methods for drawing
+        // the scene is drawn into the offscreen texture, not straight to the surface, so the
+        // post-process stack can run over it afterwards
         {
             let color_attachment = wgpu::RenderPassColorAttachment {
-                view: &view,
+                view: &self.scene_texture.view,
                 resolve_target: None,
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color {
-                        r: 0.3,
-                        g: 0.5,
-                        b: 0.9,
-                        a: 1.0,
-                    }),
+                    load: wgpu::LoadOp::Clear(self.clear_color),
                     store: wgpu::StoreOp::Store,
                 },
             };
@@ -182,9 +529,40 @@ impl<'w> State<'w> {
                 ..Default::default()
             });
 
-            // example triangle
-            render_pass.set_pipeline(&self.pipeline);
-            render_pass.draw(0..3, 0..1);
+            // example quad
+            if self.use_color {
+                render_pass.set_pipeline(&self.pipeline);
+            } else {
+                render_pass.set_pipeline(&self.pipeline_position);
+                render_pass.set_bind_group(0, &self.screen_size_bind_group, &[]);
+            }
+            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+            render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+        }
+
+        // run the filter stack over the scene texture, ping-ponging between the two
+        // intermediate textures and finishing on the surface; with no filters pushed, copy the
+        // scene straight across instead
+        if self.post_process.is_empty() {
+            encoder.copy_texture_to_texture(
+                self.scene_texture.texture.as_image_copy(),
+                frame.texture.as_image_copy(),
+                wgpu::Extent3d {
+                    width: self.surface_config.width,
+                    height: self.surface_config.height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        } else {
+            self.post_process.run(
+                &mut encoder,
+                &self.device,
+                &self.scene_texture.view,
+                &self.ping_texture,
+                &self.pong_texture,
+                &view,
+            );
         }
 
         self.queue.submit(once(encoder.finish()));
@@ -196,19 +574,62 @@ impl<'w> State<'w> {
 
 pub fn run() {
     // initialize the appropiate logger
+    #[cfg(not(target_arch = "wasm32"))]
     env_logger::init();
+    #[cfg(target_arch = "wasm32")]
+    {
+        console_error_panic_hook::set_once();
+        console_log::init_with_level(log::Level::Debug).expect("failed to initialize logger");
+    }
 
     // create the main app components
+    let config = StateConfig::default();
     let event_loop = EventLoop::new().expect("Failed to create an event loop");
-    let window = WindowBuilder::new()
+    let window_builder = WindowBuilder::new()
         .with_inner_size(PhysicalSize::new(800, 800))
         .with_title("wgpu experiments")
+        .with_transparent(config.transparent);
+
+    // extend the window content under the titlebar so the clear color fills the whole window
+    #[cfg(target_os = "macos")]
+    let window_builder = {
+        use winit::platform::macos::WindowBuilderExtMacOS;
+        window_builder
+            .with_titlebar_transparent(true)
+            .with_fullsize_content_view(true)
+    };
+
+    let window = window_builder
         .build(&event_loop)
         .expect("failed to create a window");
     debug!("the main window was created");
 
+    // on the web there is no window to draw into until we attach our canvas to the dom
+    #[cfg(target_arch = "wasm32")]
+    {
+        use winit::platform::web::WindowExtWebSys;
+
+        web_sys::window()
+            .and_then(|win| win.document())
+            .and_then(|doc| doc.body())
+            .and_then(|body| {
+                body.append_child(&web_sys::Element::from(window.canvas()?))
+                    .ok()
+            })
+            .expect("failed to attach canvas to the dom");
+    }
+
+    // native builds can block the current thread until the app is done, wasm can't block the
+    // browser's event loop so we spawn the future on the microtask queue instead
+    #[cfg(not(target_arch = "wasm32"))]
+    block_on(run_event_loop(event_loop, window, config));
+    #[cfg(target_arch = "wasm32")]
+    wasm_bindgen_futures::spawn_local(run_event_loop(event_loop, window, config));
+}
+
+async fn run_event_loop(event_loop: EventLoop<()>, window: Window, config: StateConfig) {
     // create the application state
-    let mut state = block_on(State::new(&window));
+    let mut state = State::new(&window, config).await;
 
     // run the application loop
     event_loop
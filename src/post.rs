@@ -0,0 +1,256 @@
+//! Offscreen post-processing: the scene is rendered into an intermediate texture, then an
+//! ordered stack of full-screen fragment filters runs over it before the result reaches the
+//! surface.
+
+use wgpu::include_wgsl;
+
+/// A single render-attachment-and-sampleable texture used as a ping-pong target between filters.
+pub struct PostTexture {
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+impl PostTexture {
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
+        Self::with_usage(device, config, label, wgpu::TextureUsages::empty())
+    }
+
+    /// Like [`PostTexture::new`], with extra usage flags added on top of the defaults. Used by
+    /// the scene texture, which also needs `COPY_SRC` so it can be blitted straight to the
+    /// surface when the filter stack is empty.
+    pub fn with_usage(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        label: &str,
+        extra_usage: wgpu::TextureUsages,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: config.width.max(1),
+                height: config.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | extra_usage,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self { texture, view }
+    }
+}
+
+/// A single full-screen fragment pass: samples the previous stage's texture and writes the next.
+pub struct FilterPass {
+    label: &'static str,
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl FilterPass {
+    fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        shader: wgpu::ShaderModuleDescriptor,
+        label: &'static str,
+    ) -> Self {
+        let shader = device.create_shader_module(shader);
+
+        let bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(label),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(label),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vert",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "frag",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            multisample: wgpu::MultisampleState::default(),
+            depth_stencil: None,
+            multiview: None,
+        });
+
+        Self {
+            label,
+            pipeline,
+            bind_group_layout,
+        }
+    }
+
+    fn run(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+        sampler: &wgpu::Sampler,
+        source: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(self.label),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        });
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(self.label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        // the vertex shader synthesizes a full-screen triangle from the vertex index alone
+        pass.draw(0..3, 0..1);
+    }
+}
+
+/// An identifier for a built-in filter shader, used to (re)build a [`FilterPass`] for the stack.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Grayscale,
+    Crt,
+    BloomThreshold,
+}
+
+impl Filter {
+    fn label(self) -> &'static str {
+        match self {
+            Filter::Grayscale => "grayscale filter",
+            Filter::Crt => "crt filter",
+            Filter::BloomThreshold => "bloom threshold filter",
+        }
+    }
+
+    fn shader(self) -> wgpu::ShaderModuleDescriptor<'static> {
+        match self {
+            Filter::Grayscale => include_wgsl!("shaders/post_grayscale.wgsl"),
+            Filter::Crt => include_wgsl!("shaders/post_crt.wgsl"),
+            Filter::BloomThreshold => include_wgsl!("shaders/post_bloom_threshold.wgsl"),
+        }
+    }
+}
+
+/// An ordered stack of full-screen filters applied after the scene is rendered, ping-ponging
+/// between two intermediate textures and finishing on the surface view.
+pub struct PostProcess {
+    sampler: wgpu::Sampler,
+    format: wgpu::TextureFormat,
+    passes: Vec<FilterPass>,
+}
+
+impl PostProcess {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("post-process sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            sampler,
+            format,
+            passes: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passes.is_empty()
+    }
+
+    pub fn push(&mut self, device: &wgpu::Device, filter: Filter) {
+        self.passes.push(FilterPass::new(
+            device,
+            self.format,
+            filter.shader(),
+            filter.label(),
+        ));
+    }
+
+    pub fn pop(&mut self) {
+        self.passes.pop();
+    }
+
+    /// Runs every filter in order, ping-ponging between `ping`/`pong` and writing the last
+    /// pass's output to `surface_view`. Does nothing if the stack is empty; callers should fall
+    /// back to presenting the scene texture directly in that case.
+    pub fn run(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        device: &wgpu::Device,
+        scene_view: &wgpu::TextureView,
+        ping: &PostTexture,
+        pong: &PostTexture,
+        surface_view: &wgpu::TextureView,
+    ) {
+        let ping_pong = [&ping.view, &pong.view];
+        let mut source = scene_view;
+        let mut index = 0;
+
+        for (i, pass) in self.passes.iter().enumerate() {
+            let last = i == self.passes.len() - 1;
+            let target = if last { surface_view } else { ping_pong[index] };
+            pass.run(encoder, device, &self.sampler, source, target);
+            source = target;
+            index = 1 - index;
+        }
+    }
+}